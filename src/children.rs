@@ -3,10 +3,162 @@
 use anyhow::Context;
 use std::{
     ffi::{OsStr, OsString},
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
     mem,
-    process::{Child, Command, Stdio},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    os::unix::process::ExitStatusExt,
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
+/// Replaces every occurrence of `needle` inside `template` with `replacement`,
+/// working byte-wise so it copes with non-UTF-8 arguments, as used by `-I`.
+fn substitute(template: &OsStr, needle: &OsStr, replacement: &OsStr) -> OsString {
+    let template = template.as_bytes();
+    let needle = needle.as_bytes();
+    if needle.is_empty() {
+        return OsStr::from_bytes(template).to_owned();
+    }
+    let mut out = Vec::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(pos) = rest
+        .windows(needle.len())
+        .position(|window| window == needle)
+    {
+        out.extend_from_slice(&rest[..pos]);
+        out.extend_from_slice(replacement.as_bytes());
+        rest = &rest[pos + needle.len()..];
+    }
+    out.extend_from_slice(rest);
+    OsString::from_vec(out)
+}
+
+/// Header line written at the start of every `--joblog` file.
+const JOBLOG_HEADER: &str = "Seq\tStart\tRuntime\tExitval\tSignal\tCommand";
+
+/// Whether `spawn()` should skip this invocation because `-I` is in effect
+/// and the input line substituted for `replstr` was blank, matching GNU
+/// xargs, which ignores blank lines in `-I` mode instead of running the
+/// utility with an empty substitution.
+fn is_blank_replstr_line(replstr: Option<&OsStr>, remaining_args: &[OsString]) -> bool {
+    replstr.is_some() && remaining_args.first().is_some_and(|a| a.is_empty())
+}
+
+/// Renders one tab-separated `--joblog` row from its already-extracted
+/// fields, so the exact format can be unit-tested without spawning a child.
+fn format_joblog_row(
+    seq: u64,
+    start_epoch: u64,
+    elapsed_secs: f64,
+    code: i32,
+    signal: i32,
+    cmdline: &str,
+) -> String {
+    format!(
+        "{}\t{}\t{:.3}\t{}\t{}\t{}",
+        seq, start_epoch, elapsed_secs, code, signal, cmdline
+    )
+}
+
+/// Aggregated result of every invocation run through a `ChildMinder`, mapped
+/// to the exit codes xargs documents.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Outcome {
+    /// Every invocation so far exited 0.
+    Success,
+    /// At least one invocation exited with a non-zero status other than 255.
+    SomeFailed,
+    /// At least one invocation was killed by a signal.
+    Signaled,
+    /// An invocation could not be started at all; carries the specific exit
+    /// code to report (127 if the utility was not found, 126 otherwise).
+    CouldNotRun(i32),
+    /// An invocation exited with status 255: xargs must stop immediately
+    /// without running the utility again.
+    Stopped255,
+}
+
+impl Outcome {
+    fn from_status(status: ExitStatus) -> Self {
+        match status.signal() {
+            Some(_) => Outcome::Signaled,
+            None => match status.code() {
+                Some(0) => Outcome::Success,
+                Some(255) => Outcome::Stopped255,
+                Some(_) => Outcome::SomeFailed,
+                None => Outcome::Success,
+            },
+        }
+    }
+
+    /// How severe this outcome is relative to others seen so far; the
+    /// highest-ranked outcome observed during a run is the one that
+    /// determines the final exit code.
+    fn rank(&self) -> u8 {
+        match self {
+            Outcome::Success => 0,
+            Outcome::SomeFailed => 1,
+            Outcome::Signaled => 2,
+            Outcome::CouldNotRun(_) => 3,
+            Outcome::Stopped255 => 4,
+        }
+    }
+
+    fn combine(self, other: Self) -> Self {
+        if other.rank() >= self.rank() {
+            other
+        } else {
+            self
+        }
+    }
+
+    /// Whether this outcome means no further invocations should be started.
+    fn is_fatal(&self) -> bool {
+        matches!(self, Outcome::Stopped255 | Outcome::CouldNotRun(_))
+    }
+
+    /// Maps to the process exit code xargs documents for this outcome.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Success => 0,
+            Outcome::SomeFailed => 123,
+            Outcome::Stopped255 => 124,
+            Outcome::Signaled => 125,
+            Outcome::CouldNotRun(code) => *code,
+        }
+    }
+}
+
+/// Signals that a previous invocation reported a fatal outcome and that
+/// `ChildMinder` has already stopped spawning further ones.
+///
+/// Returned by `spawn()` so that callers (the `Parser` action closure) can
+/// unwind out of the input-reading loop without treating this as a genuine
+/// I/O or usage error; the real status is available from `ChildMinder::outcome`.
+#[derive(Debug)]
+pub(crate) struct StopRequested;
+
+impl fmt::Display for StopRequested {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stopping: a previous invocation reported a fatal error")
+    }
+}
+
+impl std::error::Error for StopRequested {}
+
+/// A child process together with the bookkeeping needed to log it once it
+/// completes.
+struct RunningChild {
+    child: Child,
+    seq: u64,
+    start_instant: Instant,
+    start_epoch: u64,
+    cmdline: String,
+}
+
 /// Invokes and manages set of child processes.
 pub(crate) struct ChildMinder {
     /// Maximum number of children operating in parallel.
@@ -19,19 +171,54 @@ pub(crate) struct ChildMinder {
     initial_args: Vec<OsString>,
 
     /// All running children.
-    children: Vec<Child>,
+    children: Vec<RunningChild>,
+
+    /// Worst outcome observed so far across all reaped children.
+    outcome: Outcome,
+
+    /// Sequence number to assign to the next spawned child.
+    next_seq: u64,
+
+    /// Destination of the `--joblog` audit trail, if requested.
+    joblog: Option<BufWriter<File>>,
+
+    /// `-I REPLSTR`: when set, each invocation is built by substituting the
+    /// single item passed to `spawn()` for every occurrence of this string
+    /// inside `initial_args`, instead of appending it.
+    replstr: Option<OsString>,
 }
 
 impl ChildMinder {
     /// Creates a new `ChildMinder` that will invoke `cmd` with `initial_args`
     /// and more arguments that will be specified in `spawn()`.
-    pub fn new<I>(max_children: usize, cmd: &OsStr, initial_args: I) -> Self
+    ///
+    /// If `joblog_path` is `Some`, one tab-separated row per completed
+    /// invocation is appended to it, preceded by a header line.
+    ///
+    /// If `replstr` is `Some`, `spawn()` expects exactly one item per call
+    /// and substitutes it into `initial_args` instead of appending it (`-I`).
+    pub fn new<I>(
+        max_children: usize,
+        cmd: &OsStr,
+        initial_args: I,
+        joblog_path: Option<&Path>,
+        replstr: Option<OsString>,
+    ) -> anyhow::Result<Self>
     where
         I: IntoIterator,
         I::Item: AsRef<OsStr>,
     {
         debug_assert!(max_children > 0);
-        Self {
+        let joblog = joblog_path
+            .map(|path| -> anyhow::Result<BufWriter<File>> {
+                let file = File::create(path)
+                    .with_context(|| format!("Can not create joblog file {}", path.display()))?;
+                let mut writer = BufWriter::new(file);
+                writeln!(writer, "{}", JOBLOG_HEADER).context("Can not write joblog header")?;
+                Ok(writer)
+            })
+            .transpose()?;
+        Ok(Self {
             max_children,
             cmd: cmd.to_owned(),
             initial_args: initial_args
@@ -39,34 +226,151 @@ impl ChildMinder {
                 .map(|i| i.as_ref().to_owned())
                 .collect(),
             children: Vec::new(),
-        }
+            outcome: Outcome::Success,
+            next_seq: 1,
+            joblog,
+            replstr,
+        })
+    }
+
+    /// Worst outcome observed so far; `exit_code()` on it is what the
+    /// process should eventually exit with.
+    pub fn outcome(&self) -> Outcome {
+        self.outcome
+    }
+
+    fn note(&mut self, outcome: Outcome) {
+        self.outcome = self.outcome.combine(outcome);
     }
 
     /// Runs a child process with the arguments specified in `new()` and `remaining_args`.
     ///
     /// May block if the maximum number of processes has been reached.
+    ///
+    /// Returns `Err(StopRequested)`, without attempting to run anything, once
+    /// a previous invocation has reported a fatal outcome (exit 255, or the
+    /// utility could not be started).
+    ///
+    /// In `-I` mode, a blank input line (an empty `remaining_args[0]`) is
+    /// skipped entirely rather than spawning the utility with the empty
+    /// string substituted in, matching GNU xargs.
     pub fn spawn(&mut self, remaining_args: &[OsString]) -> anyhow::Result<()> {
+        if self.outcome.is_fatal() {
+            return Err(StopRequested.into());
+        }
+        if is_blank_replstr_line(self.replstr.as_deref(), remaining_args) {
+            return Ok(());
+        }
         if self.children.len() >= self.max_children {
-            // TODO: Naive as the oldest child is not necessarily the one that will end up first.
-            let mut child = self.children.swap_remove(0);
-            child.wait()?;
-            // TODO: log error/bail out if the child failed.
+            self.reap_one()?;
+            if self.outcome.is_fatal() {
+                return Err(StopRequested.into());
+            }
+        }
+        let args = self.build_args(remaining_args);
+        match Command::new(&self.cmd).args(&args).stdin(Stdio::null()).spawn() {
+            Ok(child) => {
+                let seq = self.next_seq;
+                self.next_seq += 1;
+                self.children.push(RunningChild {
+                    child,
+                    seq,
+                    start_instant: Instant::now(),
+                    start_epoch: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                    cmdline: self.cmdline(&args),
+                });
+                Ok(())
+            }
+            Err(e) => {
+                let code = if e.kind() == io::ErrorKind::NotFound { 127 } else { 126 };
+                eprintln!("xargs: {}: {}", self.cmd.to_string_lossy(), e);
+                self.note(Outcome::CouldNotRun(code));
+                Err(StopRequested.into())
+            }
+        }
+    }
+
+    /// Builds the full argument list for one invocation: `initial_args` with
+    /// `remaining_args` appended, or, in `-I` mode, `initial_args` with every
+    /// occurrence of `replstr` substituted by the single item in
+    /// `remaining_args`.
+    fn build_args(&self, remaining_args: &[OsString]) -> Vec<OsString> {
+        match &self.replstr {
+            Some(replstr) => {
+                let item = remaining_args.first().cloned().unwrap_or_default();
+                self.initial_args
+                    .iter()
+                    .map(|a| substitute(a, replstr, &item))
+                    .collect()
+            }
+            None => {
+                let mut args = self.initial_args.clone();
+                args.extend_from_slice(remaining_args);
+                args
+            }
         }
-        let child = Command::new(&self.cmd)
-            .args(&self.initial_args)
-            .args(remaining_args)
-            .stdin(Stdio::null())
-            .spawn()
-            .context("Can not start child process")?;
-        self.children.push(child);
-        Ok(())
+    }
+
+    /// Renders the full command line a given invocation was run with, for
+    /// the joblog.
+    fn cmdline(&self, args: &[OsString]) -> String {
+        let mut parts = Vec::with_capacity(1 + args.len());
+        parts.push(self.cmd.to_string_lossy().into_owned());
+        parts.extend(args.iter().map(|a| a.to_string_lossy().into_owned()));
+        parts.join(" ")
+    }
+
+    /// Blocks until any of the running children terminates, then removes it
+    /// from `children`.
+    ///
+    /// Unlike waiting on a fixed slot, this reaps whichever child actually
+    /// exits first, so a pool of `-P N` children stays saturated instead of
+    /// stalling behind the oldest one.
+    fn reap_one(&mut self) -> anyhow::Result<()> {
+        let mut status: libc::c_int = 0;
+        // SAFETY: pid -1 waits for any child of this process; `status` is a
+        // valid pointer to a local `c_int` as required by waitpid(2).
+        let pid = unsafe { libc::waitpid(-1, &mut status, 0) };
+        if pid == -1 {
+            return Err(io::Error::last_os_error()).context("waitpid failed");
+        }
+        let index = self
+            .children
+            .iter()
+            .position(|rc| rc.child.id() == pid as u32)
+            .expect("waitpid returned a pid not present in children");
+        let reaped = self.children.swap_remove(index);
+        self.record(reaped, ExitStatus::from_raw(status))
     }
 
     pub fn wait_all(&mut self) -> anyhow::Result<()> {
         // Take ownership of children to avoid iterating them again in drop().
-        for mut c in mem::take(&mut self.children) {
-            c.wait().context("Waiting for child process failed")?;
+        for mut reaped in mem::take(&mut self.children) {
+            let status = reaped.child.wait().context("Waiting for child process failed")?;
+            self.record(reaped, status)?;
+        }
+        Ok(())
+    }
+
+    /// Appends a joblog row for `reaped`, if `--joblog` was requested, and
+    /// folds its exit status into the aggregated `outcome`.
+    fn record(&mut self, reaped: RunningChild, status: ExitStatus) -> anyhow::Result<()> {
+        if let Some(writer) = &mut self.joblog {
+            let elapsed = reaped.start_instant.elapsed().as_secs_f64();
+            let row = format_joblog_row(
+                reaped.seq,
+                reaped.start_epoch,
+                elapsed,
+                status.code().unwrap_or(-1),
+                status.signal().unwrap_or(0),
+                &reaped.cmdline,
+            );
+            writeln!(writer, "{}", row).context("Can not write to joblog file")?;
         }
+        self.note(Outcome::from_status(status));
         Ok(())
     }
 }
@@ -76,3 +380,52 @@ impl Drop for ChildMinder {
         self.wait_all().unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joblog_header_is_tab_separated() {
+        assert_eq!(JOBLOG_HEADER, "Seq\tStart\tRuntime\tExitval\tSignal\tCommand");
+    }
+
+    #[test]
+    fn joblog_row_is_tab_separated_with_three_decimal_runtime() {
+        assert_eq!(
+            format_joblog_row(3, 1_700_000_000, 1.5, 0, 0, "echo a b"),
+            "3\t1700000000\t1.500\t0\t0\techo a b"
+        );
+    }
+
+    #[test]
+    fn joblog_row_reports_signal_and_no_exit_code() {
+        assert_eq!(
+            format_joblog_row(1, 0, 0.001, -1, 9, "sleep 10"),
+            "1\t0\t0.001\t-1\t9\tsleep 10"
+        );
+    }
+
+    #[test]
+    fn blank_replstr_line_is_skipped() {
+        let replstr = OsString::from("{}");
+        assert!(is_blank_replstr_line(
+            Some(&replstr),
+            &[OsString::from("")]
+        ));
+    }
+
+    #[test]
+    fn non_blank_replstr_line_is_not_skipped() {
+        let replstr = OsString::from("{}");
+        assert!(!is_blank_replstr_line(
+            Some(&replstr),
+            &[OsString::from("a")]
+        ));
+    }
+
+    #[test]
+    fn blank_line_without_replstr_is_not_skipped() {
+        assert!(!is_blank_replstr_line(None, &[OsString::from("")]));
+    }
+}
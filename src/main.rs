@@ -6,7 +6,6 @@
 //!     - Maximum command-line length (hardcoded on Windows).
 //!     - Conversions between OsString and [u8].
 //! - TODO: implement missing options.
-//! - TODO: enhance error handling (do not leave errors escape out of main()...).
 //! - TODO: add integration tests.
 //! - TODO: Integrate in rust findutils.
 
@@ -14,6 +13,8 @@ use std::{
     iter::Iterator,
     ffi::OsStr,
     io::{self, Read},
+    mem,
+    path::Path,
 };
 
 use anyhow::{Context, bail};
@@ -23,15 +24,32 @@ mod parser;
 use parser::Parser;
 
 mod children;
-use children::ChildMinder;
+use children::{ChildMinder, StopRequested};
 
 mod options {
     pub const CMD: &str = "CMD";
     pub const INITIAL_ARGS: &str = "INITIAL_ARGS";
     pub const MAX_BYTES: &str = "MAX_BYTES";
+    pub const NUL_DELIM: &str = "NUL_DELIM";
+    pub const DELIM: &str = "DELIM";
+    pub const MAX_PROCS: &str = "MAX_PROCS";
+    pub const JOBLOG: &str = "JOBLOG";
+    pub const MAX_ARGS: &str = "MAX_ARGS";
+    pub const REPLSTR: &str = "REPLSTR";
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() {
+    match run() {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("xargs: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs the tool and returns the xargs-compatible exit code to use.
+fn run() -> anyhow::Result<i32> {
     let matches = App::new("xargs")
         .about("Construct argument lists and execute utility.")
         .arg(
@@ -52,6 +70,50 @@ fn main() -> anyhow::Result<()> {
                 .takes_value(true)
                 .short("-s"),
         )
+        .arg(
+            Arg::with_name(options::NUL_DELIM)
+                .help("Input items are terminated by a NUL character instead of whitespace")
+                .short("0")
+                .conflicts_with(options::DELIM),
+        )
+        .arg(
+            Arg::with_name(options::DELIM)
+                .help("Input items are terminated by the first byte of CHAR instead of whitespace (\\n, \\t, \\0 and \\\\ are recognized as escapes)")
+                .takes_value(true)
+                .value_name("CHAR")
+                .short("d")
+                .long("delimiter"),
+        )
+        .arg(
+            Arg::with_name(options::MAX_PROCS)
+                .help("Run up to MAX_PROCS utility invocations in parallel")
+                .takes_value(true)
+                .value_name("MAX_PROCS")
+                .short("P"),
+        )
+        .arg(
+            Arg::with_name(options::JOBLOG)
+                .help("Log completed invocations (sequence, start, runtime, exit code, signal, command) to FILE")
+                .takes_value(true)
+                .value_name("FILE")
+                .long("joblog"),
+        )
+        .arg(
+            Arg::with_name(options::MAX_ARGS)
+                .help("Use at most MAX_ARGS arguments per command line")
+                .takes_value(true)
+                .value_name("MAX_ARGS")
+                .short("n")
+                .conflicts_with(options::REPLSTR),
+        )
+        .arg(
+            Arg::with_name(options::REPLSTR)
+                .help("Replace occurrences of REPLSTR in the initial arguments with one input line per invocation")
+                .takes_value(true)
+                .value_name("REPLSTR")
+                .short("I")
+                .conflicts_with_all(&[options::MAX_ARGS, options::NUL_DELIM, options::DELIM]),
+        )
         .get_matches();
     let cmd = matches.value_of_os(options::CMD).unwrap();
     let initial_args = matches.values_of_os(options::INITIAL_ARGS).unwrap_or_default();
@@ -59,78 +121,163 @@ fn main() -> anyhow::Result<()> {
         Some(s) => s.parse::<usize>().context("Invalid argument to -s")?,
         None => max_os_cmd_line_len(),
     };
-    let max_remaining_args_len = max_cmd_line_len as isize - initial_cmd_line_len(cmd, initial_args.clone()) as isize - 1;
+    let replstr = matches.value_of_os(options::REPLSTR).map(|s| s.to_owned());
+    let delimiter = if replstr.is_some() {
+        // -I reads exactly one argument per line.
+        Some(b'\n')
+    } else if matches.is_present(options::NUL_DELIM) {
+        Some(0u8)
+    } else {
+        matches
+            .value_of(options::DELIM)
+            .map(parse_delimiter)
+            .transpose()?
+    };
+    let max_args = if replstr.is_some() {
+        Some(1)
+    } else {
+        matches
+            .value_of(options::MAX_ARGS)
+            .map(|s| s.parse::<usize>().context("Invalid argument to -n"))
+            .transpose()?
+    };
+    let max_procs = match matches.value_of(options::MAX_PROCS) {
+        Some(s) => s.parse::<usize>().context("Invalid argument to -P")?,
+        None => 1,
+    };
+    if max_procs == 0 {
+        bail!("max-procs (-P) must be greater than 0");
+    }
+    let max_remaining_args_len = max_cmd_line_len as isize - initial_cmd_line_len(cmd, initial_args.clone()) as isize;
     if max_remaining_args_len < 1 {
         bail!("initial command line length ({}) too big for selected maximum size ({})", initial_cmd_line_len(cmd, initial_args.clone()), max_cmd_line_len);
     }
 
     let mut stdin = io::stdin();
     let mut buf = [0u8];
-    let mut minder = ChildMinder::new(1, cmd, initial_args.clone());
-    let mut parser = Parser::new(max_remaining_args_len as usize, |args| {
+    let joblog_path = matches.value_of_os(options::JOBLOG).map(Path::new);
+    let mut minder = ChildMinder::new(max_procs, cmd, initial_args.clone(), joblog_path, replstr)?;
+    let mut parser = Parser::new(max_remaining_args_len as usize, delimiter, max_args, |args| {
         minder.spawn(args)
     });
 
+    let mut stopped = false;
     loop {
         match stdin.read(&mut buf[..]) {
             Ok(0) => break,
-            Ok(_) => parser.handle_byte(buf[0])?,
+            Ok(_) => {
+                if let Err(e) = parser.handle_byte(buf[0]) {
+                    if e.downcast_ref::<StopRequested>().is_none() {
+                        return Err(e);
+                    }
+                    stopped = true;
+                    break;
+                }
+            }
             Err(e) => return Err(e).context("Failed to read from stdin"),
         }
     }
 
-    parser.handle_eof()?;
+    if !stopped {
+        if let Err(e) = parser.handle_eof() {
+            if e.downcast_ref::<StopRequested>().is_none() {
+                return Err(e);
+            }
+        }
+    }
 
     minder.wait_all()?;
 
-    Ok(())
+    Ok(minder.outcome().exit_code())
 }
 
-/// Returns length in bytes of `cmd` and `args`.
+/// Parses the argument to `-d` into the single byte it designates.
 ///
-/// TODO: xargs man page states that zero terminators should be counted.
+/// Recognizes the common backslash escapes (`\n`, `\t`, `\0`, `\\`); any
+/// other input is taken literally and its first byte is used.
+fn parse_delimiter(s: &str) -> anyhow::Result<u8> {
+    let byte = match s.as_bytes() {
+        [b'\\', b'n'] => b'\n',
+        [b'\\', b't'] => b'\t',
+        [b'\\', b'0'] => 0,
+        [b'\\', b'\\'] => b'\\',
+        [first, ..] => *first,
+        [] => bail!("Argument to -d must not be empty"),
+    };
+    Ok(byte)
+}
+
+/// Returns length in bytes of `cmd` and `args`, counting each one's NUL
+/// terminator and its pointer-sized slot in the `argv` array, matching how
+/// `Parser` accounts for the arguments it batches.
 fn initial_cmd_line_len<I>(cmd: &OsStr, args: I) -> usize
 where
     I: IntoIterator,
     I::Item: AsRef<OsStr>,
 {
+    let per_arg_overhead = 1 + mem::size_of::<usize>();
     args.into_iter()
-        .fold(cmd.len(), |acc, i| acc + i.as_ref().len() + 1)
+        .fold(cmd.len() + per_arg_overhead, |acc, i| {
+            acc + i.as_ref().len() + per_arg_overhead
+        })
+}
+
+/// Returns the size in bytes the current environment occupies in the
+/// `execve(2)` argument space: each `KEY=VALUE` string plus its NUL
+/// terminator, plus one pointer-sized slot per entry in the `envp` array.
+fn environment_len() -> usize {
+    let ptr_size = mem::size_of::<usize>();
+    std::env::vars_os()
+        .map(|(k, v)| k.len() + v.len() + 2 + ptr_size)
+        .sum()
 }
 
 /// Returns maximum length in bytes of command-line (command + all arguments) supported by OS.
-///
-/// TODO: xargs man page states it uses ARG_MAX - 4096
 fn max_os_cmd_line_len() -> usize {
     // SAFETY: No memory safety issue as this function takes and return a scalar.
     let max = unsafe { libc::sysconf(libc::_SC_ARG_MAX) };
     if max == -1 {
         panic!("Cannot get maximum command-line length");
     }
-    // TODO: _SC_ARG_MAX is the maximum size of the all argv passed to exec(2) and environment.
-    // We do not compute the environment size so reserve some hopefully big enough space for it.
-    (max / 2) as usize
+    // GNU xargs' own rule of thumb: leave ARG_MAX - 4096 bytes after
+    // subtracting what the environment already uses.
+    (max as usize)
+        .saturating_sub(environment_len())
+        .saturating_sub(4096)
 }
 
 #[cfg(test)]
 mod test_initial_cmd_line_len {
     use super::*;
 
+    fn overhead() -> usize {
+        1 + mem::size_of::<usize>()
+    }
+
     #[test]
     fn no_argument() {
         let zero_args: &[&OsStr] = &[];
-        assert_eq!(initial_cmd_line_len(OsStr::new("x"), zero_args), 1);
-        assert_eq!(initial_cmd_line_len(OsStr::new("yy"), zero_args), 2);
+        assert_eq!(initial_cmd_line_len(OsStr::new("x"), zero_args), 1 + overhead());
+        assert_eq!(initial_cmd_line_len(OsStr::new("yy"), zero_args), 2 + overhead());
     }
 
     #[test]
     fn single_argument() {
-        assert_eq!(initial_cmd_line_len(OsStr::new("x"), &[OsStr::new("y")]), 3);
-        assert_eq!(initial_cmd_line_len(OsStr::new("x"), &[OsStr::new("zz")]), 4);
+        assert_eq!(
+            initial_cmd_line_len(OsStr::new("x"), &[OsStr::new("y")]),
+            1 + overhead() + 1 + overhead()
+        );
+        assert_eq!(
+            initial_cmd_line_len(OsStr::new("x"), &[OsStr::new("zz")]),
+            1 + overhead() + 2 + overhead()
+        );
     }
 
     #[test]
     fn several_arguments() {
-        assert_eq!(initial_cmd_line_len(OsStr::new("x"), &[OsStr::new("y"), OsStr::new("z")]), 5);
+        assert_eq!(
+            initial_cmd_line_len(OsStr::new("x"), &[OsStr::new("y"), OsStr::new("z")]),
+            1 + overhead() + 1 + overhead() + 1 + overhead()
+        );
     }
-}
\ No newline at end of file
+}
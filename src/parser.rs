@@ -1,13 +1,35 @@
 //! Stdin parser.
 
 use std::ffi::{OsStr, OsString};
+use std::mem;
 use std::os::unix::ffi::OsStrExt;
 
-/// Breaks down input bytes into space-separated arguments and accumulates them
-/// until maximum size reached.
+use anyhow::bail;
+
+/// Per-argument bookkeeping overhead charged towards `max_len`: the NUL
+/// terminator xargs appends to the argument plus the pointer-sized slot it
+/// occupies in the `argv` array passed to `execve(2)`.
+const PER_ARG_OVERHEAD: usize = 1 + mem::size_of::<usize>();
+
+/// Quoting state of the argument currently being parsed in whitespace mode.
 ///
-/// - TODO: Quoting.
-/// - TODO: Zero-separated words.
+/// Mirrors the subset of shell quoting GNU xargs supports: single quotes,
+/// double quotes and backslash escapes outside of quotes.
+#[derive(PartialEq, Eq)]
+enum QuoteState {
+    /// Not inside a quoted section; whitespace separates arguments and a
+    /// backslash escapes the next byte.
+    Unquoted,
+    /// Inside a `'...'` section; every byte is literal except `'`.
+    Single,
+    /// Inside a `"..."` section; every byte is literal except `"`.
+    Double,
+    /// Just saw a backslash while `Unquoted`; the next byte is literal.
+    Escaped,
+}
+
+/// Breaks down input bytes into arguments and accumulates them until maximum
+/// size reached.
 pub(crate) struct Parser<F>
 where
     F: FnMut(&[OsString]) -> anyhow::Result<()>,
@@ -15,7 +37,8 @@ where
     /// All arguments accumulated so far.
     args: Vec<OsString>,
 
-    /// Current length in bytes of arguments in `args` including separators.
+    /// Current length in bytes of arguments in `args`, including each
+    /// argument's `PER_ARG_OVERHEAD`.
     cur_len: usize,
 
     /// Maximum length in bytes of all arguments.
@@ -24,6 +47,27 @@ where
     /// Argument being parsed.
     arg: Vec<u8>,
 
+    /// Byte separating arguments on input.
+    ///
+    /// `None` means the default whitespace-splitting mode, where runs of
+    /// whitespace are collapsed and leading/trailing whitespace is ignored.
+    /// `Some(byte)` means every occurrence of `byte` ends the current
+    /// argument, even if that argument is empty, as used by `-0`/`-d`.
+    delimiter: Option<u8>,
+
+    /// Quoting state, only meaningful when `delimiter` is `None`.
+    quote: QuoteState,
+
+    /// Whether a quote has been opened and closed for the argument being
+    /// parsed, even if it contributed zero bytes (e.g. `''`). Distinguishes
+    /// "no argument pending" from "an empty argument is pending" in
+    /// `handle_space`, since both leave `arg` empty.
+    saw_quote: bool,
+
+    /// Maximum number of arguments per invocation, regardless of how much of
+    /// `max_len` is left, as set by `-n` (or forced to `Some(1)` by `-I`).
+    max_args: Option<usize>,
+
     /// Closure called when concatenating `arg` to `args` would exceed `max_len`.
     action: F,
 }
@@ -31,29 +75,97 @@ where
 impl<F: FnMut(&[OsString]) -> anyhow::Result<()>> Parser<F> {
     /// Creates a new parser that will accumulate arguments up to `max_len`
     /// bytes and repeatedly call `action` with accumulated arguments.
-    pub fn new(max_len: usize, action: F) -> Self {
+    ///
+    /// `delimiter` selects the input mode: `None` for whitespace-splitting,
+    /// `Some(byte)` to split on a single fixed byte instead (e.g. NUL for
+    /// `-0`, or any byte chosen via `-d`; `-I` also uses this with `b'\n'`).
+    ///
+    /// `max_args` additionally caps every invocation at that many arguments,
+    /// even if more would still fit in `max_len`, as set by `-n` or `-I`.
+    pub fn new(max_len: usize, delimiter: Option<u8>, max_args: Option<usize>, action: F) -> Self {
         Self {
             max_len,
             args: Vec::new(),
             cur_len: 0,
             arg: Vec::new(),
+            delimiter,
+            quote: QuoteState::Unquoted,
+            saw_quote: false,
+            max_args,
             action,
         }
     }
 
     /// Parses incoming byte.
     pub fn handle_byte(&mut self, ch: u8) -> anyhow::Result<()> {
-        if (ch as char).is_ascii_whitespace() {
-            self.handle_space()?;
-        } else {
-            self.arg.push(ch);
+        match self.delimiter {
+            Some(d) => {
+                if ch == d {
+                    self.handle_delimiter()?;
+                } else {
+                    self.arg.push(ch);
+                }
+            }
+            None => self.handle_byte_quoted(ch)?,
+        }
+        Ok(())
+    }
+
+    /// Parses one byte of whitespace-mode input, honoring quotes and
+    /// backslash escapes.
+    fn handle_byte_quoted(&mut self, ch: u8) -> anyhow::Result<()> {
+        match self.quote {
+            QuoteState::Escaped => {
+                self.arg.push(ch);
+                self.quote = QuoteState::Unquoted;
+            }
+            QuoteState::Single => {
+                if ch == b'\'' {
+                    self.quote = QuoteState::Unquoted;
+                } else {
+                    self.arg.push(ch);
+                }
+            }
+            QuoteState::Double => {
+                if ch == b'"' {
+                    self.quote = QuoteState::Unquoted;
+                } else {
+                    self.arg.push(ch);
+                }
+            }
+            QuoteState::Unquoted => match ch {
+                b'\'' => {
+                    self.quote = QuoteState::Single;
+                    self.saw_quote = true;
+                }
+                b'"' => {
+                    self.quote = QuoteState::Double;
+                    self.saw_quote = true;
+                }
+                b'\\' => self.quote = QuoteState::Escaped,
+                _ if (ch as char).is_ascii_whitespace() => self.handle_space()?,
+                _ => self.arg.push(ch),
+            },
         }
         Ok(())
     }
 
     /// Flushes accumulated arguments on EOF.
     pub fn handle_eof(&mut self) -> anyhow::Result<()> {
-        self.handle_space()?;
+        if self.delimiter.is_none() && self.quote != QuoteState::Unquoted {
+            bail!("unterminated quote or backslash in input");
+        }
+        match self.delimiter {
+            // A trailing delimiter must not produce an empty trailing
+            // argument, so only flush `arg` here if something follows the
+            // last delimiter seen.
+            Some(_) => {
+                if !self.arg.is_empty() {
+                    self.handle_delimiter()?;
+                }
+            }
+            None => self.handle_space()?,
+        }
         if !self.args.is_empty() {
             (self.action)(&self.args)?;
         }
@@ -67,24 +179,48 @@ impl<F: FnMut(&[OsString]) -> anyhow::Result<()>> Parser<F> {
             self.args.clear();
             self.cur_len = 0;
         }
-        if !self.arg.is_empty() {
+        if !self.arg.is_empty() || self.saw_quote {
             self.append_arg();
+            self.flush_if_max_args_reached()?;
+        }
+        Ok(())
+    }
+
+    /// Like `handle_space` but always ends the current argument, even if
+    /// empty, since in delimiter mode empty fields between two delimiters
+    /// are significant.
+    fn handle_delimiter(&mut self) -> anyhow::Result<()> {
+        if self.is_break_down_needed() {
+            (self.action)(&self.args)?;
+            self.args.clear();
+            self.cur_len = 0;
         }
+        self.append_arg();
+        self.flush_if_max_args_reached()?;
         Ok(())
     }
 
     fn is_break_down_needed(&self) -> bool {
-        let separator_len = if !self.args.is_empty() { 1 } else { 0 };
-        self.cur_len + separator_len + self.arg.len() > self.max_len
+        self.cur_len + self.arg.len() + PER_ARG_OVERHEAD > self.max_len
     }
 
     fn append_arg(&mut self) {
-        if !self.args.is_empty() {
-            self.cur_len += 1;
-        }
+        self.cur_len += self.arg.len() + PER_ARG_OVERHEAD;
         self.args.push(OsStr::from_bytes(&self.arg).to_owned());
-        self.cur_len += self.arg.len();
         self.arg.clear();
+        self.saw_quote = false;
+    }
+
+    /// Flushes `args` once it reaches `max_args`, independent of `max_len`.
+    fn flush_if_max_args_reached(&mut self) -> anyhow::Result<()> {
+        if let Some(max_args) = self.max_args {
+            if self.args.len() >= max_args {
+                (self.action)(&self.args)?;
+                self.args.clear();
+                self.cur_len = 0;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -92,12 +228,31 @@ impl<F: FnMut(&[OsString]) -> anyhow::Result<()>> Parser<F> {
 mod tests {
     use super::*;
 
-    /// Builds parser for `max_len`, passes it `input` and returns the parsed
-    /// arguments as an array of lines, each line containing the arguments for
-    /// one invocation.
+    /// Builds parser for `max_len` in whitespace mode, passes it `input` and
+    /// returns the parsed arguments as an array of lines, each line
+    /// containing the arguments for one invocation.
     fn run(max_len: usize, input: &[u8]) -> anyhow::Result<Vec<Vec<String>>> {
+        run_with_delimiter(max_len, None, input)
+    }
+
+    /// Like `run` but with an explicit delimiter mode.
+    fn run_with_delimiter(
+        max_len: usize,
+        delimiter: Option<u8>,
+        input: &[u8],
+    ) -> anyhow::Result<Vec<Vec<String>>> {
+        run_with_policy(max_len, delimiter, None, input)
+    }
+
+    /// Like `run` but with an explicit delimiter and `-n`-style max-args cap.
+    fn run_with_policy(
+        max_len: usize,
+        delimiter: Option<u8>,
+        max_args: Option<usize>,
+        input: &[u8],
+    ) -> anyhow::Result<Vec<Vec<String>>> {
         let mut lines = Vec::<Vec<String>>::new();
-        let mut p = Parser::new(max_len, |args| {
+        let mut p = Parser::new(max_len, delimiter, max_args, |args| {
             lines.push(args.iter().map(|oss| oss.to_str().unwrap().to_owned()).collect());
             Ok(())
         });
@@ -122,14 +277,94 @@ mod tests {
 
     #[test]
     fn all_args_fit_in_single_line() -> anyhow::Result<()> {
-        assert_eq!(run(3, b"x y")?, [["x", "y"]]);
-        assert_eq!(run(3, b"x y ")?, [["x", "y"]]);
+        let max = 2 * (1 + PER_ARG_OVERHEAD);
+        assert_eq!(run(max, b"x y")?, [["x", "y"]]);
+        assert_eq!(run(max, b"x y ")?, [["x", "y"]]);
         Ok(())
     }
 
     #[test]
     fn break_down_needed() -> anyhow::Result<()> {
-        assert_eq!(run(3, b"x yz")?, [["x"], ["yz"]]);
+        let max = 1 + PER_ARG_OVERHEAD;
+        assert_eq!(run(max, b"x yz")?, [["x"], ["yz"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn nul_delimiter_splits_on_embedded_whitespace() -> anyhow::Result<()> {
+        assert_eq!(
+            run_with_delimiter(42, Some(0), b"a b\0c\0")?,
+            [["a b", "c"]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nul_delimiter_ignores_trailing_delimiter() -> anyhow::Result<()> {
+        assert_eq!(run_with_delimiter(42, Some(0), b"a\0")?, [["a"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn custom_delimiter_keeps_empty_fields() -> anyhow::Result<()> {
+        assert_eq!(
+            run_with_delimiter(42, Some(b','), b"a,,b,")?,
+            [["a", "", "b"]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn single_quotes_protect_whitespace() -> anyhow::Result<()> {
+        assert_eq!(run(42, b"'a b' c")?, [["a b", "c"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn double_quotes_protect_whitespace() -> anyhow::Result<()> {
+        assert_eq!(run(42, b"\"a b\" c")?, [["a b", "c"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn single_quotes_do_not_honor_backslash() -> anyhow::Result<()> {
+        assert_eq!(run(42, b"'a\\b'")?, [["a\\b"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn backslash_escapes_whitespace() -> anyhow::Result<()> {
+        assert_eq!(run(42, b"a\\ b c")?, [["a b", "c"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_quotes_produce_an_empty_argument() -> anyhow::Result<()> {
+        assert_eq!(run(42, b"'' c")?, [["", "c"]]);
+        assert_eq!(run(42, b"\"\" c")?, [["", "c"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn unterminated_quote_is_an_error() {
+        assert!(run(42, b"'a b").is_err());
+    }
+
+    #[test]
+    fn max_args_caps_batch_size_regardless_of_byte_budget() -> anyhow::Result<()> {
+        assert_eq!(
+            run_with_policy(1024, None, Some(2), b"a b c d e")?,
+            vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn one_arg_per_line_mode_for_dash_i() -> anyhow::Result<()> {
+        assert_eq!(
+            run_with_policy(1024, Some(b'\n'), Some(1), b"a b\nc\n")?,
+            [["a b"], ["c"]]
+        );
         Ok(())
     }
 }